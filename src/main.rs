@@ -1,17 +1,15 @@
 #[cfg(feature = "cli")]
-use std::fs::File;
-#[cfg(feature = "cli")]
-use std::io::BufReader;
-#[cfg(feature = "cli")]
-use std::io::Read;
-#[cfg(feature = "cli")]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
 #[cfg(feature = "cli")]
 use clap::Parser as ClapParser;
 #[cfg(feature = "cli")]
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+#[cfg(feature = "cli")]
 use log::{error, info};
+#[cfg(feature = "cli")]
+use rayon::prelude::*;
 
 #[cfg(feature = "cli")]
 use wir2wav::*;
@@ -29,6 +27,30 @@ struct Cli {
 
     #[arg(long, short = 'o', help = "A directory to place outputs")]
     dst: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Float32,
+        help = "Output sample format (float32, pcm16, pcm24)"
+    )]
+    format: OutputFormat,
+
+    #[arg(
+        long,
+        conflicts_with = "no_dither",
+        help = "Force-enable TPDF dithering for integer output (default: on for pcm16)"
+    )]
+    dither: bool,
+
+    #[arg(long = "no-dither", help = "Force-disable TPDF dithering for integer output")]
+    no_dither: bool,
+
+    #[arg(
+        long,
+        help = "Verify file_size/body alignment before decoding and log a CRC32 of the decoded samples"
+    )]
+    verify: bool,
 }
 
 #[cfg(not(feature = "cli"))]
@@ -38,62 +60,185 @@ fn main() -> ExitCode {
     ExitCode::FAILURE
 }
 
-#[cfg(feature = "cli")]
-fn main() -> ExitCode {
-    let args = Cli::parse_from(wild::args());
-    let dst = args.dst.unwrap_or("./".to_string());
-    let dst_dir = PathBuf::from(dst);
-    if !dst_dir.is_dir() || !dst_dir.try_exists().unwrap() {
-        error!("Destination directory is invalid. Abort.");
-        return ExitCode::FAILURE;
+/// Raises the process's open-file-descriptor soft limit to the hard limit,
+/// so a large parallel batch doesn't fail with "too many open files".
+#[cfg(all(feature = "cli", unix))]
+fn raise_fd_limit() {
+    match rlimit::Resource::NOFILE.get() {
+        Ok((soft, hard)) if soft < hard => {
+            if let Err(error) = rlimit::Resource::NOFILE.set(hard, hard) {
+                error!("failed to raise the open-file-descriptor limit: {}", error);
+            }
+        }
+        Ok(_) => (),
+        Err(error) => error!("failed to read the open-file-descriptor limit: {}", error),
     }
+}
 
-    for src in args.srcs {
-        let srcpath = PathBuf::from(&src);
-        info!("path: {}", &src);
-        let mut file = BufReader::new(match File::open(&srcpath) {
-            Ok(file) => file,
-            Err(error) => {
-                error!("{}", error);
-                return ExitCode::FAILURE;
-            }
-        });
-        let mut buf = vec![];
-        info!("start reading...");
-        match file.read_to_end(&mut buf) {
-            Ok(_) => (),
-            Err(error) => {
-                error!("error: {}", error);
-                return ExitCode::FAILURE;
-            }
-        };
+#[cfg(all(feature = "cli", not(unix)))]
+fn raise_fd_limit() {}
+
+/// Converts a single source file (WIR -> WAV, or WAV -> WIR by extension).
+/// Returns `true` on success.
+#[cfg(feature = "cli")]
+fn convert_one(
+    src: &str,
+    dst_dir: &Path,
+    format: OutputFormat,
+    dither: bool,
+    verify: bool,
+    multi: &MultiProgress,
+) -> bool {
+    let srcpath = PathBuf::from(src);
+    info!("path: {}", src);
 
-        let mut parser = Parser::new(buf);
-        info!("start parsing a wir file");
-        let mut wir = match parser.parse() {
+    let is_wav = srcpath
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"));
+
+    if is_wav {
+        info!("start converting wav -> wir");
+        let wir = match WirWriter::from_wav(&srcpath) {
             Ok(wir) => wir,
             Err(error) => {
-                error!("Parse failed: {}", error);
-                return ExitCode::FAILURE;
+                multi.suspend(|| error!("{}: {}", src, error));
+                return false;
             }
         };
-        info!("WirHeader: {:?}", wir.header);
-
-        info!("create a wavspec...");
-        let spec = wir.header.to_wavspec();
 
-        let src_with_wav = &srcpath.with_extension("wav");
-        let filename = match src_with_wav.file_name() {
+        let src_with_wir = &srcpath.with_extension("wir");
+        let filename = match src_with_wir.file_name() {
             Some(filename) => filename,
             None => {
-                error!("Filename not found. Potentially non-file is specified?");
-                return ExitCode::FAILURE;
+                multi.suspend(|| {
+                    error!("{}: filename not found. Potentially non-file is specified?", src)
+                });
+                return false;
             }
         };
 
         let dst_path = dst_dir.join(filename);
-        wir.write_to_wav(dst_path, spec).unwrap();
+        if let Err(error) = wir.write_to_wir(dst_path) {
+            multi.suspend(|| error!("{}: {}", src, error));
+            return false;
+        }
+        return true;
+    }
+
+    info!("start mapping the wir file");
+    let mut parser = match Parser::open_mmap(&srcpath) {
+        Ok(parser) => parser,
+        Err(error) => {
+            multi.suspend(|| error!("{}: {}", src, error));
+            return false;
+        }
+    };
+
+    info!("start parsing the wir header");
+    let mut header = match parser.parse_header() {
+        Ok(header) => header,
+        Err(error) => {
+            multi.suspend(|| error!("{}: {}", src, error));
+            return false;
+        }
+    };
+    if verify {
+        if let Err(error) = parser.verify_file_size(&header) {
+            multi.suspend(|| error!("{}: {}", src, error));
+            return false;
+        }
+    }
+    info!("WirHeader: {:?}", header);
+
+    info!("create a wavspec...");
+    let spec = header.to_wavspec(format);
+
+    let src_with_wav = &srcpath.with_extension("wav");
+    let filename = match src_with_wav.file_name() {
+        Some(filename) => filename,
+        None => {
+            multi.suspend(|| {
+                error!("{}: filename not found. Potentially non-file is specified?", src)
+            });
+            return false;
+        }
+    };
+
+    let dst_path = dst_dir.join(filename);
+
+    if verify {
+        // CRC32 requires the decoded body, so there's no avoiding a full
+        // materialization on this path.
+        let body = match parser.parse_body(&header) {
+            Ok(body) => body,
+            Err(error) => {
+                multi.suspend(|| error!("{}: {}", src, error));
+                return false;
+            }
+        };
+        info!("crc32: {:08x}", body_crc32(&body));
+        let mut wir = Wir { header, body };
+        if let Err(error) = wir.write_to_wav(dst_path, spec, format, dither) {
+            multi.suspend(|| error!("{}: {}", src, error));
+            return false;
+        }
+    } else if let Err(error) =
+        Wir::stream_to_wav(&header, &mut parser, dst_path, spec, format, dither)
+    {
+        multi.suspend(|| error!("{}: {}", src, error));
+        return false;
+    }
+
+    true
+}
+
+#[cfg(feature = "cli")]
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let args = Cli::parse_from(wild::args());
+    let dst = args.dst.unwrap_or("./".to_string());
+    let dst_dir = PathBuf::from(dst);
+    if !dst_dir.is_dir() || !dst_dir.try_exists().unwrap() {
+        error!("Destination directory is invalid. Abort.");
+        return ExitCode::FAILURE;
     }
 
-    ExitCode::SUCCESS
+    raise_fd_limit();
+
+    let dither = if args.no_dither {
+        false
+    } else if args.dither {
+        true
+    } else {
+        args.format == OutputFormat::Pcm16
+    };
+
+    let multi = MultiProgress::new();
+    let overall_bar = multi.add(ProgressBar::new(args.srcs.len() as u64));
+    overall_bar.set_style(
+        ProgressStyle::with_template("{msg} [{bar:40}] {pos}/{len}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    overall_bar.set_message("converting");
+
+    let results: Vec<bool> = args
+        .srcs
+        .par_iter()
+        .map(|src| {
+            let ok = convert_one(src, &dst_dir, args.format, dither, args.verify, &multi);
+            overall_bar.inc(1);
+            ok
+        })
+        .collect();
+
+    overall_bar.finish_and_clear();
+
+    if results.into_iter().any(|ok| !ok) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
 }